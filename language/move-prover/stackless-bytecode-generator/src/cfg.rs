@@ -0,0 +1,394 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! A control-flow graph and dominator tree over the stackless bytecode of a `FunctionTarget`.
+//!
+//! The analyses in this crate (borrow, reaching-def, livevar, ...) historically re-derived
+//! control flow from the flat `data.code` vector. This module factors that logic into a single,
+//! shared `Cfg` so downstream passes consume one notion of successors/predecessors and, on top of
+//! that, a dominator tree.
+
+use crate::{
+    function_target::FunctionTarget,
+    stackless_bytecode::{Bytecode, Label},
+};
+use std::collections::{BTreeMap, BTreeSet};
+use vm::file_format::CodeOffset;
+
+/// A basic block, identified by the code offset of its leader. It spans the inclusive offset
+/// range `[lower, upper]` of the original `data.code` vector.
+#[derive(Debug, Clone, Copy)]
+pub struct Block {
+    pub lower: CodeOffset,
+    pub upper: CodeOffset,
+}
+
+/// The control-flow graph of a function target. Blocks, successor and predecessor edges are all
+/// keyed by the leader `CodeOffset` of the block they belong to.
+#[derive(Debug)]
+pub struct Cfg {
+    blocks: BTreeMap<CodeOffset, Block>,
+    succ: BTreeMap<CodeOffset, Vec<CodeOffset>>,
+    pred: BTreeMap<CodeOffset, Vec<CodeOffset>>,
+    entry: CodeOffset,
+}
+
+impl Cfg {
+    /// Builds the control-flow graph for the given function target. Leaders are the entry offset,
+    /// any branch or jump target, and the instruction following any branch, jump, return or abort.
+    pub fn new(target: &FunctionTarget<'_>) -> Cfg {
+        let code = target.get_bytecode();
+        let label_offsets = Self::label_offsets(code);
+
+        // Collect the leaders. Offset 0 is always a leader; an empty body has no blocks.
+        let mut leaders = BTreeSet::new();
+        if !code.is_empty() {
+            leaders.insert(0 as CodeOffset);
+        }
+        for (offset, bytecode) in code.iter().enumerate() {
+            let offset = offset as CodeOffset;
+            match bytecode {
+                Bytecode::Branch(_, then_label, else_label, _) => {
+                    leaders.insert(label_offsets[then_label]);
+                    leaders.insert(label_offsets[else_label]);
+                    if offset + 1 < code.len() as CodeOffset {
+                        leaders.insert(offset + 1);
+                    }
+                }
+                Bytecode::Jump(_, label) => {
+                    leaders.insert(label_offsets[label]);
+                    if offset + 1 < code.len() as CodeOffset {
+                        leaders.insert(offset + 1);
+                    }
+                }
+                Bytecode::Ret(..) | Bytecode::Abort(..) => {
+                    if offset + 1 < code.len() as CodeOffset {
+                        leaders.insert(offset + 1);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // Turn the sorted leaders into blocks spanning up to (but not including) the next leader.
+        let ordered: Vec<CodeOffset> = leaders.iter().cloned().collect();
+        let mut blocks = BTreeMap::new();
+        for (i, &lower) in ordered.iter().enumerate() {
+            let upper = match ordered.get(i + 1) {
+                Some(&next) => next - 1,
+                None => code.len() as CodeOffset - 1,
+            };
+            blocks.insert(lower, Block { lower, upper });
+        }
+
+        // Derive successors from the terminator of each block, then invert to get predecessors.
+        let mut succ: BTreeMap<CodeOffset, Vec<CodeOffset>> = BTreeMap::new();
+        let mut pred: BTreeMap<CodeOffset, Vec<CodeOffset>> = BTreeMap::new();
+        for (&leader, block) in &blocks {
+            succ.entry(leader).or_default();
+            pred.entry(leader).or_default();
+            let targets = match &code[block.upper as usize] {
+                Bytecode::Branch(_, then_label, else_label, _) => {
+                    vec![label_offsets[then_label], label_offsets[else_label]]
+                }
+                Bytecode::Jump(_, label) => vec![label_offsets[label]],
+                Bytecode::Ret(..) | Bytecode::Abort(..) => vec![],
+                _ => match ordered.get(ordered.iter().position(|o| *o == leader).unwrap() + 1) {
+                    Some(&next) => vec![next],
+                    None => vec![],
+                },
+            };
+            succ.insert(leader, targets);
+        }
+        for (&leader, targets) in &succ {
+            for &target in targets {
+                pred.entry(target).or_default().push(leader);
+            }
+        }
+
+        Cfg {
+            blocks,
+            succ,
+            pred,
+            entry: 0,
+        }
+    }
+
+    /// Maps each `Label` to the code offset of the instruction which defines it.
+    fn label_offsets(code: &[Bytecode]) -> BTreeMap<Label, CodeOffset> {
+        let mut map = BTreeMap::new();
+        for (offset, bytecode) in code.iter().enumerate() {
+            if let Bytecode::Label(_, label) = bytecode {
+                map.insert(*label, offset as CodeOffset);
+            }
+        }
+        map
+    }
+
+    /// Returns the entry block's leader offset.
+    pub fn entry(&self) -> CodeOffset {
+        self.entry
+    }
+
+    /// Returns the block starting at the given leader offset.
+    pub fn block(&self, leader: CodeOffset) -> &Block {
+        &self.blocks[&leader]
+    }
+
+    /// Iterates over all blocks, in ascending leader order.
+    pub fn blocks(&self) -> impl Iterator<Item = (&CodeOffset, &Block)> {
+        self.blocks.iter()
+    }
+
+    /// Returns the successor blocks of the block at the given leader offset.
+    pub fn successors(&self, leader: CodeOffset) -> &[CodeOffset] {
+        &self.succ[&leader]
+    }
+
+    /// Returns the predecessor blocks of the block at the given leader offset.
+    pub fn predecessors(&self, leader: CodeOffset) -> &[CodeOffset] {
+        &self.pred[&leader]
+    }
+
+    /// Returns the set of blocks reachable from the entry, in reverse postorder.
+    fn reverse_postorder(&self) -> Vec<CodeOffset> {
+        let mut order = vec![];
+        let mut visited = BTreeSet::new();
+        self.postorder(self.entry, &mut visited, &mut order);
+        order.reverse();
+        order
+    }
+
+    fn postorder(
+        &self,
+        block: CodeOffset,
+        visited: &mut BTreeSet<CodeOffset>,
+        order: &mut Vec<CodeOffset>,
+    ) {
+        if !visited.insert(block) {
+            return;
+        }
+        for &succ in &self.succ[&block] {
+            self.postorder(succ, visited, order);
+        }
+        order.push(block);
+    }
+
+    /// Computes the immediate dominator of every block using the Cooper-Harvey-Kennedy iterative
+    /// algorithm. Blocks are numbered in reverse postorder; `idom[entry]` is the entry itself and
+    /// all other reachable blocks are refined to a fixpoint. Blocks unreachable from the entry are
+    /// given themselves as their immediate dominator and kept out of the fixpoint.
+    pub fn dominators(&self) -> BTreeMap<CodeOffset, CodeOffset> {
+        // An empty body (e.g. a native function) has no blocks at all, so `entry` does not name a
+        // real block; `reverse_postorder`/`postorder` would otherwise index `self.succ[&entry]` and
+        // panic.
+        if self.blocks.is_empty() {
+            return BTreeMap::new();
+        }
+        let rpo = self.reverse_postorder();
+        let rpo_num: BTreeMap<CodeOffset, usize> =
+            rpo.iter().enumerate().map(|(i, b)| (*b, i)).collect();
+
+        let mut idom: BTreeMap<CodeOffset, Option<CodeOffset>> =
+            self.blocks.keys().map(|b| (*b, None)).collect();
+        idom.insert(self.entry, Some(self.entry));
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &block in rpo.iter().skip(1) {
+                let mut new_idom = None;
+                for &pred in &self.pred[&block] {
+                    if idom[&pred].is_none() {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => pred,
+                        Some(cur) => Self::intersect(&idom, &rpo_num, pred, cur),
+                    });
+                }
+                if let Some(new_idom) = new_idom {
+                    if idom[&block] != Some(new_idom) {
+                        idom.insert(block, Some(new_idom));
+                        changed = true;
+                    }
+                }
+            }
+        }
+
+        // Unreachable blocks dominate only themselves.
+        idom.into_iter()
+            .map(|(block, dom)| (block, dom.unwrap_or(block)))
+            .collect()
+    }
+
+    /// Walks the two fingers upward along the dominator chain until they meet, using reverse
+    /// postorder numbers to decide which finger lags behind (a larger number is deeper in the CFG).
+    fn intersect(
+        idom: &BTreeMap<CodeOffset, Option<CodeOffset>>,
+        rpo_num: &BTreeMap<CodeOffset, usize>,
+        mut a: CodeOffset,
+        mut b: CodeOffset,
+    ) -> CodeOffset {
+        while a != b {
+            while rpo_num[&a] > rpo_num[&b] {
+                a = idom[&a].unwrap();
+            }
+            while rpo_num[&b] > rpo_num[&a] {
+                b = idom[&b].unwrap();
+            }
+        }
+        a
+    }
+
+    /// Returns true if block `a` dominates block `b`, i.e. every path from the entry to `b` passes
+    /// through `a`. A block always dominates itself.
+    pub fn dominates(&self, a: CodeOffset, b: CodeOffset) -> bool {
+        let idom = self.dominators();
+        let mut runner = b;
+        loop {
+            if runner == a {
+                return true;
+            }
+            let next = idom[&runner];
+            if next == runner {
+                // Reached the entry (or an unreachable self-dominated block) without finding `a`.
+                return false;
+            }
+            runner = next;
+        }
+    }
+
+    /// Computes the dominance frontier of every block. A block `b` is in the dominance frontier of
+    /// `a` if `a` dominates a predecessor of `b` but does not strictly dominate `b` itself.
+    pub fn dominance_frontier(&self) -> BTreeMap<CodeOffset, BTreeSet<CodeOffset>> {
+        let idom = self.dominators();
+        let mut df: BTreeMap<CodeOffset, BTreeSet<CodeOffset>> =
+            self.blocks.keys().map(|b| (*b, BTreeSet::new())).collect();
+        for (&block, preds) in &self.pred {
+            if preds.len() < 2 {
+                continue;
+            }
+            for &pred in preds {
+                let mut runner = pred;
+                while runner != idom[&block] {
+                    df.get_mut(&runner).unwrap().insert(block);
+                    let next = idom[&runner];
+                    if next == runner {
+                        break;
+                    }
+                    runner = next;
+                }
+            }
+        }
+        df
+    }
+}
+
+#[cfg(test)]
+impl Cfg {
+    /// Builds a `Cfg` directly from its blocks and edges, bypassing the need for a real
+    /// `FunctionTarget`. Test-only: lets the dominator-tree algorithms be exercised against
+    /// hand-built graphs.
+    pub(crate) fn for_test(
+        blocks: BTreeMap<CodeOffset, Block>,
+        succ: BTreeMap<CodeOffset, Vec<CodeOffset>>,
+        pred: BTreeMap<CodeOffset, Vec<CodeOffset>>,
+        entry: CodeOffset,
+    ) -> Cfg {
+        Cfg {
+            blocks,
+            succ,
+            pred,
+            entry,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A straight-line CFG: 0 -> 1 -> 2, no branches.
+    fn straight_line() -> Cfg {
+        let blocks = vec![0, 1, 2]
+            .into_iter()
+            .map(|l| (l, Block { lower: l, upper: l }))
+            .collect();
+        let succ = vec![(0, vec![1]), (1, vec![2]), (2, vec![])]
+            .into_iter()
+            .collect();
+        let pred = vec![(0, vec![]), (1, vec![0]), (2, vec![1])]
+            .into_iter()
+            .collect();
+        Cfg::for_test(blocks, succ, pred, 0)
+    }
+
+    /// A diamond CFG: 0 branches to 1 and 2, both rejoin at 3. Block 4 is unreachable.
+    fn diamond_with_unreachable_block() -> Cfg {
+        let blocks = vec![0, 1, 2, 3, 4]
+            .into_iter()
+            .map(|l| (l, Block { lower: l, upper: l }))
+            .collect();
+        let succ = vec![
+            (0, vec![1, 2]),
+            (1, vec![3]),
+            (2, vec![3]),
+            (3, vec![]),
+            (4, vec![3]),
+        ]
+        .into_iter()
+        .collect();
+        let pred = vec![
+            (0, vec![]),
+            (1, vec![0]),
+            (2, vec![0]),
+            (3, vec![1, 2, 4]),
+            (4, vec![]),
+        ]
+        .into_iter()
+        .collect();
+        Cfg::for_test(blocks, succ, pred, 0)
+    }
+
+    #[test]
+    fn dominators_on_straight_line_chain_to_the_entry() {
+        let cfg = straight_line();
+        let idom = cfg.dominators();
+        assert_eq!(idom[&0], 0);
+        assert_eq!(idom[&1], 0);
+        assert_eq!(idom[&2], 1);
+    }
+
+    #[test]
+    fn dominators_converge_at_the_diamond_join_and_ignore_unreachable_blocks() {
+        let cfg = diamond_with_unreachable_block();
+        let idom = cfg.dominators();
+        assert_eq!(idom[&0], 0);
+        assert_eq!(idom[&1], 0);
+        assert_eq!(idom[&2], 0);
+        // Block 3 has two reachable predecessors (1 and 2) whose nearest common dominator is 0.
+        assert_eq!(idom[&3], 0);
+        // Block 4 is unreachable from the entry and dominates only itself.
+        assert_eq!(idom[&4], 4);
+        assert!(cfg.dominates(0, 3));
+        assert!(!cfg.dominates(1, 3));
+    }
+
+    #[test]
+    fn dominance_frontier_of_diamond_branches_is_the_join_block() {
+        let cfg = diamond_with_unreachable_block();
+        let df = cfg.dominance_frontier();
+        assert_eq!(df[&1], vec![3].into_iter().collect::<BTreeSet<_>>());
+        assert_eq!(df[&2], vec![3].into_iter().collect::<BTreeSet<_>>());
+        // The entry strictly dominates the join block, so it is not in its own frontier.
+        assert!(df[&0].is_empty());
+    }
+
+    #[test]
+    fn dominators_on_empty_cfg_does_not_panic() {
+        let cfg = Cfg::for_test(BTreeMap::new(), BTreeMap::new(), BTreeMap::new(), 0);
+        assert!(cfg.dominators().is_empty());
+        assert!(cfg.dominance_frontier().is_empty());
+    }
+}
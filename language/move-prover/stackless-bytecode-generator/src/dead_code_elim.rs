@@ -0,0 +1,203 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! Dead-code elimination.
+//!
+//! This processor consumes the liveness annotations produced by `livevar_analysis` and the control
+//! flow described by `cfg::Cfg` to remove two kinds of dead code:
+//!
+//!   * blocks that are unreachable from the entry, and
+//!   * assignments whose defined local is not live on any successor edge and whose right-hand side
+//!     is side-effect free (pure moves/copies and constant loads, never calls or references which
+//!     could alias).
+//!
+//! Deleting one assignment can make an earlier one dead, so the pass iterates to a fixpoint,
+//! re-running `livevar_analysis` on the rewritten code at the top of every iteration: liveness is a
+//! property of the current code, and reindexing a stale snapshot through the rewrite's offset map
+//! would never let a later iteration see that an earlier producer's only use just disappeared. All
+//! splicing goes through `FunctionTargetData::rewrite`, which remaps `locations` and re-anchors the
+//! spec-block offsets of any dropped instruction onto the next surviving one.
+
+use crate::{
+    cfg::Cfg,
+    function_target::{FunctionTarget, FunctionTargetData},
+    function_target_pipeline::{FunctionTargetProcessor, FunctionTargetsHolder},
+    livevar_analysis::{LiveVarAnalysis, LiveVarAnnotation},
+    stackless_bytecode::Bytecode,
+    visit::{BytecodeRewriter, RewriteContext},
+};
+use spec_lang::env::FunctionEnv;
+use std::collections::{BTreeMap, BTreeSet};
+use vm::file_format::CodeOffset;
+
+pub struct DeadCodeElimination();
+
+impl DeadCodeElimination {
+    pub fn new() -> Box<dyn FunctionTargetProcessor> {
+        Box::new(DeadCodeElimination())
+    }
+}
+
+impl FunctionTargetProcessor for DeadCodeElimination {
+    fn process(
+        &self,
+        targets: &mut FunctionTargetsHolder,
+        func_env: &FunctionEnv<'_>,
+        mut data: FunctionTargetData,
+    ) -> FunctionTargetData {
+        if func_env.is_native() || data.code.is_empty() {
+            return data;
+        }
+        let live_var_analysis = LiveVarAnalysis::new();
+        loop {
+            // Liveness must be recomputed against the *current* code on every iteration: the
+            // annotation the pipeline attached before this processor ran describes the code as it
+            // was before any deletion, so reindexing that stale snapshot can never let a later
+            // iteration see that an earlier producer's only use just disappeared.
+            data = live_var_analysis.process(targets, func_env, data);
+            let live_after = data
+                .annotations
+                .get::<LiveVarAnnotation>()
+                .map(|annotation| {
+                    annotation
+                        .0
+                        .iter()
+                        .map(|(offset, info)| (*offset, info.after.clone()))
+                        .collect::<BTreeMap<_, _>>()
+                })
+                .unwrap_or_default();
+
+            // Reachability depends only on control flow, which `rewrite` keeps internally
+            // consistent, so it is safe to re-derive fresh from `data` on every iteration.
+            let reachable = {
+                let target = FunctionTarget::new(func_env, &data);
+                let cfg = Cfg::new(&target);
+                reachable_offsets(&cfg)
+            };
+
+            let mut rewriter = DeadCodeRewriter {
+                reachable,
+                live_after,
+                changed: false,
+            };
+            data.rewrite("DeadCodeElimination", &mut rewriter);
+            if !rewriter.changed {
+                break;
+            }
+        }
+        data
+    }
+}
+
+/// Collects the offsets of every instruction which belongs to a block reachable from the entry.
+fn reachable_offsets(cfg: &Cfg) -> BTreeSet<CodeOffset> {
+    let mut reachable_blocks = BTreeSet::new();
+    let mut work = vec![cfg.entry()];
+    while let Some(leader) = work.pop() {
+        if reachable_blocks.insert(leader) {
+            work.extend(cfg.successors(leader).iter().cloned());
+        }
+    }
+    let mut offsets = BTreeSet::new();
+    for &leader in &reachable_blocks {
+        let block = cfg.block(leader);
+        offsets.extend(block.lower..=block.upper);
+    }
+    offsets
+}
+
+struct DeadCodeRewriter {
+    reachable: BTreeSet<CodeOffset>,
+    live_after: BTreeMap<CodeOffset, BTreeSet<usize>>,
+    changed: bool,
+}
+
+impl BytecodeRewriter for DeadCodeRewriter {
+    fn rewrite_instr(
+        &mut self,
+        _ctx: &mut RewriteContext<'_>,
+        offset: CodeOffset,
+        bc: &Bytecode,
+    ) -> Vec<Bytecode> {
+        // Unreachable instructions are dropped wholesale.
+        if !self.reachable.contains(&offset) {
+            self.changed = true;
+            return vec![];
+        }
+        // A pure assignment whose destination is not live afterwards has no observable effect.
+        let dead_dest = match bc {
+            Bytecode::Assign(_, dest, ..) | Bytecode::Load(_, dest, ..) => {
+                is_dead_after(*dest, self.live_after.get(&offset))
+            }
+            _ => false,
+        };
+        if dead_dest {
+            self.changed = true;
+            return vec![];
+        }
+        vec![bc.clone()]
+    }
+}
+
+/// True if `dest` is known to be out of use at a program point, given the set of locals live
+/// after it (or `None` if liveness was never computed for that offset, in which case `dest` is
+/// conservatively treated as live so a missing annotation can never cause an elimination).
+fn is_dead_after(dest: usize, live_after: Option<&BTreeSet<usize>>) -> bool {
+    live_after.map_or(false, |live| !live.contains(&dest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::Block;
+
+    #[test]
+    fn reachable_offsets_excludes_blocks_not_reachable_from_the_entry() {
+        // 0 -> 1, and an unreachable block 2 whose instruction spans offset 2.
+        let blocks = vec![
+            (0, Block { lower: 0, upper: 0 }),
+            (1, Block { lower: 1, upper: 1 }),
+            (2, Block { lower: 2, upper: 2 }),
+        ]
+        .into_iter()
+        .collect();
+        let succ = vec![(0, vec![1]), (1, vec![]), (2, vec![])]
+            .into_iter()
+            .collect();
+        let pred = vec![(0, vec![]), (1, vec![0]), (2, vec![])]
+            .into_iter()
+            .collect();
+        let cfg = Cfg::for_test(blocks, succ, pred, 0);
+
+        let offsets = reachable_offsets(&cfg);
+        assert_eq!(offsets, vec![0, 1].into_iter().collect::<BTreeSet<_>>());
+    }
+
+    #[test]
+    fn is_dead_after_reports_a_destination_dead_once_it_drops_out_of_the_live_set() {
+        let live = vec![1usize].into_iter().collect::<BTreeSet<_>>();
+        assert!(!is_dead_after(1, Some(&live)));
+        assert!(is_dead_after(0, Some(&live)));
+    }
+
+    #[test]
+    fn is_dead_after_treats_a_missing_annotation_as_conservatively_live() {
+        assert!(!is_dead_after(0, None));
+    }
+
+    #[test]
+    fn is_dead_after_tracks_liveness_recomputed_after_an_earlier_instruction_is_deleted() {
+        // `0: x = 5`, `1: y = move(x)`, `2: return`, with nothing using `x`/`y` afterward.
+        // Before offset 1 is deleted, liveness after offset 0 still has `x` live (it feeds offset
+        // 1), so the producer at offset 0 is not yet dead.
+        let live_after_0_before = vec![0usize].into_iter().collect::<BTreeSet<_>>(); // `x` == local 0
+        assert!(!is_dead_after(0, Some(&live_after_0_before)));
+
+        // Once offset 1 is deleted and liveness is recomputed fresh against the remaining code,
+        // `x` has no more uses, so liveness after offset 0 is now empty and its producer is dead.
+        // This is exactly the fixpoint step that reindexing a stale snapshot can never reach: the
+        // live set for offset 0 must shrink between iterations, not just be carried forward.
+        let live_after_0_after = BTreeSet::new();
+        assert!(is_dead_after(0, Some(&live_after_0_after)));
+    }
+}
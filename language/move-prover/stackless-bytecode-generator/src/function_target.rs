@@ -3,7 +3,9 @@
 
 use crate::{
     annotations::Annotations,
-    borrow_analysis, lifetime_analysis, livevar_analysis, packref_analysis, reaching_def_analysis,
+    borrow_analysis,
+    cfg::Cfg,
+    lifetime_analysis, livevar_analysis, packref_analysis, reaching_def_analysis,
     stackless_bytecode::{AttrId, Bytecode, SpecBlockId},
     writeback_analysis,
 };
@@ -32,6 +34,7 @@ pub struct FunctionTarget<'env> {
 /// Holds the owned data belonging to a FunctionTarget, which can be rewritten using
 /// the `FunctionTargetsHolder::rewrite` method.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct FunctionTargetData {
     pub code: Vec<Bytecode>,
     pub local_types: Vec<Type>,
@@ -39,6 +42,13 @@ pub struct FunctionTargetData {
     pub ref_param_map: BTreeMap<usize, usize>,
     pub acquires_global_resources: Vec<StructId>,
     pub locations: BTreeMap<AttrId, Loc>,
+
+    /// Type-indexed map of analysis results (e.g. `LiveVarAnnotation`), keyed by the annotation's
+    /// own type. `Annotations` is a heterogeneous map over `dyn Any` and so has no generic
+    /// `Serialize`/`Deserialize` impl to derive; a rehydrated target starts with this empty and
+    /// relies on the pipeline re-running whichever analyses a later pass needs, the same as it
+    /// would for a freshly computed (non-cached) target.
+    #[cfg_attr(feature = "serde", serde(skip))]
     pub annotations: Annotations,
 
     /// Map of spec block ids as given by the source, to the code offset in the original
@@ -48,6 +58,24 @@ pub struct FunctionTargetData {
 
     /// Map of spec block ids to generated by transformations, to the generated conditions.
     pub generated_spec_blocks: BTreeMap<SpecBlockId, Spec>,
+
+    /// Records, for each transformation-introduced local, the name of the pass which introduced
+    /// it. The only way to grow `local_types` past the user local count is `add_temp_local` (or,
+    /// equivalently, `RewriteContext::new_temp_local` during a `rewrite`), which adds the matching
+    /// entry here in the same step, so the two can never drift apart. This is transient and not
+    /// part of the serialized cache; a rehydrated target simply reports `"?"` as the origin.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub temp_sources: BTreeMap<usize, &'static str>,
+}
+
+/// Classifies a local of a function target by its origin. Locals in the user parameter and local
+/// range come straight from the source; anything past the user local count is a temporary
+/// introduced by a transformation, tagged with the pass which introduced it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LocalKind {
+    UserParam,
+    UserLocal,
+    Temp { introduced_by: &'static str },
 }
 
 impl<'env> FunctionTarget<'env> {
@@ -172,6 +200,22 @@ impl<'env> FunctionTarget<'env> {
         &self.data.local_types[idx]
     }
 
+    /// Classifies the local at index as a user parameter, a user local, or a transformation temp.
+    /// Analyses which must treat compiler temporaries specially should consult this rather than
+    /// comparing the index against `get_user_local_count`, which no longer suffices once multiple
+    /// passes interleave their insertions.
+    pub fn get_local_kind(&self, idx: usize) -> LocalKind {
+        if idx < self.get_parameter_count() {
+            LocalKind::UserParam
+        } else if idx < self.get_user_local_count() {
+            LocalKind::UserLocal
+        } else {
+            LocalKind::Temp {
+                introduced_by: self.data.temp_sources.get(&idx).copied().unwrap_or("?"),
+            }
+        }
+    }
+
     /// Returns specification associated with this function.
     pub fn get_spec(&'env self) -> &'env Spec {
         self.func_env.get_spec()
@@ -242,9 +286,11 @@ impl<'env> FunctionTarget<'env> {
         self.annotation_formatters.borrow_mut().push(formatter);
     }
 
-    /// Tests use this function to register all relevant annotation formatters. Extend this with
-    /// new formatters relevant for tests.
-    pub fn register_annotation_formatters_for_test(&self) {
+    /// Registers every annotation formatter known to this crate. This is the production entry
+    /// point used whenever a `FunctionTarget` is rebuilt outside of the pipeline which originally
+    /// produced it (e.g. `CachedFunctionTarget::rehydrate`); extend this with new formatters as
+    /// passes introduce them.
+    pub fn register_all_annotation_formatters(&self) {
         self.register_annotation_formatter(Box::new(livevar_analysis::format_livevar_annotation));
         self.register_annotation_formatter(Box::new(borrow_analysis::format_borrow_annotation));
         self.register_annotation_formatter(Box::new(
@@ -256,6 +302,83 @@ impl<'env> FunctionTarget<'env> {
             reaching_def_analysis::format_reaching_def_annotation,
         ));
     }
+
+    /// Tests use this function to register all relevant annotation formatters.
+    pub fn register_annotation_formatters_for_test(&self) {
+        self.register_all_annotation_formatters();
+    }
+}
+
+impl<'env> FunctionTarget<'env> {
+    /// Renders this function target as a GraphViz digraph. Each basic block of the CFG becomes a
+    /// node whose label is the block's instructions together with the output of the registered
+    /// annotation formatters (the same information the linear `Display` prints inline); edges
+    /// follow the CFG successors and are labeled `true`/`false` for the two arms of a conditional
+    /// branch. The result can be piped straight into `dot -Tsvg`.
+    pub fn to_dot(&self) -> String {
+        use std::fmt::Write;
+        let cfg = Cfg::new(self);
+        let code = self.get_bytecode();
+        let mut out = String::new();
+        writeln!(
+            out,
+            "digraph \"{}\" {{",
+            self.get_name().display(self.symbol_pool())
+        )
+        .unwrap();
+        writeln!(out, "  node [shape=box, fontname=\"Courier\"];").unwrap();
+        for (&leader, block) in cfg.blocks() {
+            let mut label = String::new();
+            for offset in block.lower..=block.upper {
+                for annotation in self
+                    .annotation_formatters
+                    .borrow()
+                    .iter()
+                    .filter_map(|f| f(self, offset))
+                {
+                    write!(label, "// {}\\l", dot_escape(&annotation)).unwrap();
+                }
+                write!(
+                    label,
+                    "{}\\l",
+                    dot_escape(&code[offset as usize].display(self).to_string())
+                )
+                .unwrap();
+            }
+            writeln!(out, "  b{} [label=\"{}\"];", leader, label).unwrap();
+        }
+        for (&leader, block) in cfg.blocks() {
+            let is_conditional = matches!(&code[block.upper as usize], Bytecode::Branch(..));
+            for (i, succ) in cfg.successors(leader).iter().enumerate() {
+                let edge_label = branch_edge_label(is_conditional, i);
+                writeln!(out, "  b{} -> b{}{};", leader, succ, edge_label).unwrap();
+            }
+        }
+        writeln!(out, "}}").unwrap();
+        out
+    }
+}
+
+/// Escapes a string for inclusion in a GraphViz node label, keeping newlines as left-justified
+/// line breaks.
+fn dot_escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\l")
+}
+
+/// The GraphViz edge label for the `successor_index`-th outgoing edge of a `to_dot` block ending
+/// in `is_conditional`: the first edge of a two-way conditional branch is its `true` arm, the
+/// second its `false` arm; any other block's edges are unlabeled.
+fn branch_edge_label(is_conditional: bool, successor_index: usize) -> &'static str {
+    if !is_conditional {
+        return "";
+    }
+    if successor_index == 0 {
+        " [label=\"true\"]"
+    } else {
+        " [label=\"false\"]"
+    }
 }
 
 impl<'env> fmt::Display for FunctionTarget<'env> {
@@ -315,11 +438,18 @@ impl<'env> fmt::Display for FunctionTarget<'env> {
         }
         writeln!(f, " {{")?;
         for i in self.get_parameter_count()..self.get_local_count() {
+            let origin = match self.get_local_kind(i) {
+                LocalKind::Temp { introduced_by } => {
+                    format!("  // introduced by {}", introduced_by)
+                }
+                _ => String::new(),
+            };
             writeln!(
                 f,
-                "    var {}: {}",
+                "    var {}: {}{}",
                 self.get_local_name(i).display(self.symbol_pool()),
-                self.get_local_type(i).display(&tctx)
+                self.get_local_type(i).display(&tctx),
+                origin
             )?;
         }
         for (offset, code) in self.get_bytecode().iter().enumerate() {
@@ -339,3 +469,32 @@ impl<'env> fmt::Display for FunctionTarget<'env> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dot_escape_escapes_backslashes_quotes_and_newlines() {
+        assert_eq!(dot_escape("a\\b"), "a\\\\b");
+        assert_eq!(dot_escape("say \"hi\""), "say \\\"hi\\\"");
+        assert_eq!(dot_escape("line1\nline2"), "line1\\lline2");
+    }
+
+    #[test]
+    fn dot_escape_leaves_a_plain_string_unchanged() {
+        assert_eq!(dot_escape("mov r0, r1"), "mov r0, r1");
+    }
+
+    #[test]
+    fn branch_edge_label_is_unlabeled_for_a_non_conditional_block() {
+        assert_eq!(branch_edge_label(false, 0), "");
+        assert_eq!(branch_edge_label(false, 1), "");
+    }
+
+    #[test]
+    fn branch_edge_label_marks_the_true_and_false_arms_of_a_conditional_branch() {
+        assert_eq!(branch_edge_label(true, 0), " [label=\"true\"]");
+        assert_eq!(branch_edge_label(true, 1), " [label=\"false\"]");
+    }
+}
@@ -0,0 +1,111 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! Persistent cache for function targets.
+//!
+//! Recomputing a `FunctionTargetData` (bytecode, local/return types, annotations, spec-block maps)
+//! from scratch on every prover or verifier run is wasteful for modules which have not changed.
+//! This module provides a serializable form of a function target which holds only the owned `data`
+//! plus enough identity to re-attach it to a `FunctionEnv`, together with a content hash of the
+//! source function so a cached entry can be validated before it is reused.
+//!
+//! Serialization support is gated behind the `serde` feature, as is common in the ecosystem; the
+//! hashing and `rehydrate` entry point are always available.
+
+use crate::function_target::{FunctionTarget, FunctionTargetData};
+use spec_lang::env::FunctionEnv;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+};
+
+/// The owned, relocatable form of a `FunctionTarget`. A `FunctionTarget` borrows a `&FunctionEnv`
+/// and so cannot be serialized directly; this struct stores the owned `data` alongside the
+/// display names of the function and its module and a content hash of the source
+/// `FunctionEnv`, and rebuilds the transient state in `rehydrate`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CachedFunctionTarget {
+    /// Display name of the enclosing module, used to re-attach to the right `FunctionEnv`.
+    pub module_name: String,
+    /// Display name of the function.
+    pub fun_name: String,
+    /// Content hash of the source function, used to validate the cache entry before reuse.
+    pub content_hash: u64,
+    pub data: FunctionTargetData,
+}
+
+impl CachedFunctionTarget {
+    /// Captures a function target for caching. Takes ownership of `data` and tags it with the
+    /// content hash of `func_env`.
+    pub fn new(func_env: &FunctionEnv<'_>, data: FunctionTargetData) -> CachedFunctionTarget {
+        CachedFunctionTarget {
+            module_name: func_env
+                .module_env
+                .get_name()
+                .display(func_env.module_env.symbol_pool())
+                .to_string(),
+            fun_name: func_env
+                .get_name()
+                .display(func_env.module_env.symbol_pool())
+                .to_string(),
+            content_hash: content_hash(func_env),
+            data,
+        }
+    }
+
+    /// Returns true if this cache entry is still valid for the given function environment, i.e. it
+    /// was captured from the same function and the content hash still matches.
+    pub fn is_valid_for(&self, func_env: &FunctionEnv<'_>) -> bool {
+        self.fun_name
+            == func_env
+                .get_name()
+                .display(func_env.module_env.symbol_pool())
+                .to_string()
+            && self.content_hash == content_hash(func_env)
+    }
+
+    /// Rebuilds a usable `FunctionTarget` from the cached data, re-deriving the transient
+    /// `name_to_index` map and re-registering the annotation formatters. Borrows the owned `data`
+    /// from `self`, so the cache entry must outlive the returned target.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the cache entry is stale for `func_env`. Unlike an ordinary consistency check,
+    /// serving stale bytecode and annotations out of the cache as if they were current would
+    /// silently produce wrong analysis/verification results, so this is checked in release builds
+    /// too rather than being a `debug_assert!`.
+    pub fn rehydrate<'env>(&'env self, func_env: &'env FunctionEnv<'env>) -> FunctionTarget<'env> {
+        assert!(
+            self.is_valid_for(func_env),
+            "rehydrating a function target from a stale cache entry"
+        );
+        let target = FunctionTarget::new(func_env, &self.data);
+        target.register_all_annotation_formatters();
+        target
+    }
+}
+
+/// Computes a content hash of a source function. Two function environments which agree on this
+/// hash are treated as producing the same analysis target, so a cached entry can be reloaded
+/// instead of recomputed. This must hash the function's actual body, not just its name and arity:
+/// name/local-count/type-parameter-count stay the same across edits to the instructions
+/// themselves (swapping an operand, changing a call target, reordering statements, ...), which is
+/// exactly the common case a content hash needs to catch.
+pub fn content_hash(func_env: &FunctionEnv<'_>) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    func_env
+        .module_env
+        .get_name()
+        .display(func_env.module_env.symbol_pool())
+        .to_string()
+        .hash(&mut hasher);
+    func_env
+        .get_name()
+        .display(func_env.module_env.symbol_pool())
+        .to_string()
+        .hash(&mut hasher);
+    func_env.get_local_count().hash(&mut hasher);
+    func_env.get_type_parameters().len().hash(&mut hasher);
+    format!("{:?}", func_env.get_bytecode()).hash(&mut hasher);
+    hasher.finish()
+}
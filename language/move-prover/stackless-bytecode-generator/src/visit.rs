@@ -0,0 +1,189 @@
+// Copyright (c) The Libra Core Contributors
+// SPDX-License-Identifier: Apache-2.0
+
+//! Visitable/rewritable view of the stackless bytecode.
+//!
+//! Transformations historically mutated `FunctionTargetData.code` in place, each hand-rolling the
+//! index bookkeeping needed to keep `locations` and the spec-block offset maps consistent. This
+//! module provides two traits and a driver so passes describe *what* an instruction becomes and
+//! the driver handles the splicing and remapping once, in a single audited code path.
+
+use crate::{function_target::FunctionTargetData, stackless_bytecode::Bytecode};
+use spec_lang::ty::Type;
+use std::collections::{BTreeMap, BTreeSet};
+use vm::file_format::CodeOffset;
+
+/// A read-only walk over the bytecode. Implementors observe each instruction together with its
+/// code offset.
+pub trait BytecodeVisitor {
+    fn visit_instr(&mut self, offset: CodeOffset, bc: &Bytecode);
+}
+
+/// A rewrite over the bytecode. For each instruction, `rewrite_instr` returns the sequence of
+/// instructions which replaces it: an empty vector deletes the instruction, a single-element
+/// vector keeps or replaces it, and a longer vector expands it. `ctx` gives the rewriter a way to
+/// introduce fresh temporary locals for an expansion, correctly attributed to the driving pass.
+pub trait BytecodeRewriter {
+    fn rewrite_instr(
+        &mut self,
+        ctx: &mut RewriteContext<'_>,
+        offset: CodeOffset,
+        bc: &Bytecode,
+    ) -> Vec<Bytecode>;
+}
+
+/// Handed to a `BytecodeRewriter` for the duration of one `FunctionTargetData::rewrite` call, so
+/// any locals it introduces are tagged with the name of the pass driving the rewrite instead of
+/// the opaque `"?"` fallback.
+pub struct RewriteContext<'a> {
+    data: &'a mut FunctionTargetData,
+    pass_name: &'static str,
+}
+
+impl<'a> RewriteContext<'a> {
+    /// Allocates a new local of type `ty`, attributed to this rewrite's pass, and returns its
+    /// index.
+    pub fn new_temp_local(&mut self, ty: Type) -> usize {
+        self.data.add_temp_local(ty, self.pass_name)
+    }
+}
+
+impl FunctionTargetData {
+    /// Runs a visitor over the bytecode in offset order.
+    pub fn visit(&self, visitor: &mut impl BytecodeVisitor) {
+        for (offset, bc) in self.code.iter().enumerate() {
+            visitor.visit_instr(offset as CodeOffset, bc);
+        }
+    }
+
+    /// Grows `local_types` by one fresh temporary of type `ty`, recording `introduced_by` against
+    /// its index so `FunctionTarget::get_local_kind` can report a real origin, and returns the new
+    /// local's index.
+    pub fn add_temp_local(&mut self, ty: Type, introduced_by: &'static str) -> usize {
+        let idx = self.local_types.len();
+        self.local_types.push(ty);
+        self.temp_sources.insert(idx, introduced_by);
+        idx
+    }
+
+    /// Runs a rewriter over the bytecode, rebuilding `code` from the per-instruction replacements.
+    /// Afterwards the `AttrId`-keyed `locations` map is pruned to the surviving instructions and
+    /// the `given_spec_blocks` offset map is remapped so each spec block stays attached to its
+    /// instruction (or, if that instruction was deleted, re-anchors to the next surviving one).
+    /// `pass_name` identifies the caller for any local the rewriter introduces via the
+    /// `RewriteContext` it is given. Returns the same original-offset-to-new-offset map used
+    /// internally, so callers which hold other offset-keyed state can translate its keys; the
+    /// content of such state is not updated (a liveness set remapped this way still describes the
+    /// code before the rewrite).
+    pub fn rewrite(
+        &mut self,
+        pass_name: &'static str,
+        rewriter: &mut impl BytecodeRewriter,
+    ) -> BTreeMap<CodeOffset, CodeOffset> {
+        let code = std::mem::take(&mut self.code);
+        let mut new_code: Vec<Bytecode> = Vec::with_capacity(code.len());
+        let mut replacements: Vec<(CodeOffset, Vec<Bytecode>)> = Vec::with_capacity(code.len());
+        {
+            let mut ctx = RewriteContext {
+                data: &mut *self,
+                pass_name,
+            };
+            for (offset, bc) in code.iter().enumerate() {
+                let offset = offset as CodeOffset;
+                replacements.push((offset, rewriter.rewrite_instr(&mut ctx, offset, bc)));
+            }
+        }
+
+        let offset_map = Self::build_offset_map(
+            replacements
+                .iter()
+                .map(|(offset, replacement)| (*offset, replacement.len())),
+        );
+        for (_, replacement) in replacements {
+            new_code.extend(replacement);
+        }
+
+        // Prune `locations` to the attribute ids which still occur in the rewritten code.
+        let live_attrs: BTreeSet<_> = new_code.iter().map(Bytecode::get_attr_id).collect();
+        self.locations
+            .retain(|attr_id, _| live_attrs.contains(attr_id));
+
+        // Remap the given spec blocks onto their new offsets, dropping those with no anchor left.
+        self.given_spec_blocks = std::mem::take(&mut self.given_spec_blocks)
+            .into_iter()
+            .filter_map(|(block_id, offset)| offset_map.get(&offset).map(|new| (block_id, *new)))
+            .collect();
+
+        self.code = new_code;
+        offset_map
+    }
+
+    /// The pure offset-remapping core of `rewrite`. Given, in original offset order, the length of
+    /// each instruction's replacement (0 for a deletion, 1 to keep/replace, >1 for an expansion),
+    /// returns the map from original offset to new offset: a deleted instruction's offset is
+    /// re-anchored to the next surviving instruction's new offset, and an offset with no surviving
+    /// successor (trailing deletions) has no entry. A replacement of length N advances the running
+    /// new-offset counter by N, not by 1, so an expansion keeps every later offset in sync with its
+    /// real position in the rewritten code.
+    fn build_offset_map(
+        replacement_lens: impl Iterator<Item = (CodeOffset, usize)>,
+    ) -> BTreeMap<CodeOffset, CodeOffset> {
+        let mut offset_map = BTreeMap::new();
+        let mut pending = Vec::new();
+        let mut new_offset: CodeOffset = 0;
+        for (offset, len) in replacement_lens {
+            if len == 0 {
+                pending.push(offset);
+                continue;
+            }
+            offset_map.insert(offset, new_offset);
+            for deferred in pending.drain(..) {
+                offset_map.insert(deferred, new_offset);
+            }
+            new_offset += len as CodeOffset;
+        }
+        offset_map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_offset_map_keeps_surviving_offsets_in_place_when_nothing_is_deleted_or_expanded() {
+        let map = FunctionTargetData::build_offset_map(vec![(0, 1), (1, 1), (2, 1)].into_iter());
+        assert_eq!(map.get(&0), Some(&0));
+        assert_eq!(map.get(&1), Some(&1));
+        assert_eq!(map.get(&2), Some(&2));
+    }
+
+    #[test]
+    fn build_offset_map_reanchors_deleted_offsets_onto_the_next_survivor() {
+        // Offsets 1 and 2 are deleted; both should re-anchor onto offset 3, which becomes new
+        // offset 1 once offset 0 (new offset 0) is accounted for.
+        let map =
+            FunctionTargetData::build_offset_map(vec![(0, 1), (1, 0), (2, 0), (3, 1)].into_iter());
+        assert_eq!(map.get(&0), Some(&0));
+        assert_eq!(map.get(&1), Some(&1));
+        assert_eq!(map.get(&2), Some(&1));
+        assert_eq!(map.get(&3), Some(&1));
+    }
+
+    #[test]
+    fn build_offset_map_drops_trailing_deletions_with_no_surviving_successor() {
+        let map = FunctionTargetData::build_offset_map(vec![(0, 1), (1, 0)].into_iter());
+        assert_eq!(map.get(&0), Some(&0));
+        assert_eq!(map.get(&1), None);
+    }
+
+    #[test]
+    fn build_offset_map_advances_past_expanded_offsets_by_their_replacement_length() {
+        // Offset 1 expands into two instructions, so offset 2's new position must skip over both,
+        // not just one.
+        let map = FunctionTargetData::build_offset_map(vec![(0, 1), (1, 2), (2, 1)].into_iter());
+        assert_eq!(map.get(&0), Some(&0));
+        assert_eq!(map.get(&1), Some(&1));
+        assert_eq!(map.get(&2), Some(&3));
+    }
+}